@@ -1,7 +1,10 @@
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Utc, Weekday};
+use evalexpr::{context_map, Value};
 use hex_literal::hex;
 use reqwest;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use teloxide::{
     adaptors::DefaultParseMode,
     prelude::*,
@@ -9,18 +12,25 @@ use teloxide::{
     utils::{command::BotCommand, markdown},
 };
 use tokio::signal;
-use tokio::sync::oneshot;
-use tokio::time::{interval, Duration};
+use tokio::sync::{broadcast, oneshot, Mutex, RwLock};
+use tokio::time::{interval, sleep, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
 use web3::{
     contract::{Contract, Options},
-    futures::StreamExt,
-    types::{FilterBuilder, H160, H256, BlockNumber},
+    futures::{Stream, StreamExt},
+    types::{FilterBuilder, Log, H160, H256, U256},
 };
 
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::error::Error;
+use std::fs;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::str::FromStr;
 
+const TOKENS_FILE: &str = "tokens.json";
+
 #[derive(Debug, Deserialize)]
 struct PancakePriceData {
     price: String,
@@ -39,73 +49,293 @@ struct PancakeResponse {
     data: PancakePriceData,
 }
 
+#[derive(Debug, Deserialize)]
+struct CoinGeckoPrice {
+    usd: f32,
+}
+
+#[async_trait]
+trait PriceSource: std::fmt::Debug + Send + Sync {
+    async fn current_price(&self, address: &str) -> Result<f32>;
+}
+
 #[derive(Debug)]
-enum PriceFeed {
-    Pancake,
-    OneInch,
+struct PancakeSource;
+
+impl PancakeSource {
+    fn price_endpoint(&self, address: &str) -> String {
+        format!("https://api.pancakeswap.info/api/v2/tokens/{}", address)
+    }
 }
 
-impl PriceFeed {
-    fn api_endpoint(&self) -> &'static str {
-        match self {
-            Self::Pancake => "https://api.pancakeswap.info/api/v2/tokens/",
-            Self::OneInch => "https://api.1inch.io/v4.0/56/quote",
-        }
+#[async_trait]
+impl PriceSource for PancakeSource {
+    async fn current_price(&self, address: &str) -> Result<f32> {
+        let json: PancakeResponse = reqwest::get(self.price_endpoint(address))
+            .await?
+            .json()
+            .await?;
+        let price: f32 = json.data.price.parse()?;
+        Ok(price)
     }
+}
 
+#[derive(Debug)]
+struct OneInchSource;
+
+impl OneInchSource {
     fn price_endpoint(&self, address: &str) -> String {
-        let mut url = reqwest::Url::parse(self.api_endpoint()).unwrap();
-        let endpoint: String;
-        match self {
-            Self::Pancake => endpoint = format!("{}{}", url.as_str(), address),
-            Self::OneInch => {
-                url.query_pairs_mut()
-                    .append_pair("fromTokenAddress", address)
-                    .append_pair(
-                        "toTokenAddress",
-                        "0xe9e7cea3dedca5984780bafc599bd69add087d56", // BUSD
-                    )
-                    .append_pair("amount", "1000");
-                endpoint = url.to_string();
+        let mut url = reqwest::Url::parse("https://api.1inch.io/v4.0/56/quote").unwrap();
+        url.query_pairs_mut()
+            .append_pair("fromTokenAddress", address)
+            .append_pair(
+                "toTokenAddress",
+                "0xe9e7cea3dedca5984780bafc599bd69add087d56", // BUSD
+            )
+            .append_pair("amount", "1000");
+        url.to_string()
+    }
+}
+
+#[async_trait]
+impl PriceSource for OneInchSource {
+    async fn current_price(&self, address: &str) -> Result<f32> {
+        let json: OneInchResponse = reqwest::get(self.price_endpoint(address))
+            .await?
+            .json()
+            .await?;
+        let from_amount: f32 = json.from_token_amount.parse()?;
+        let to_amount: f32 = json.to_token_amount.parse()?;
+        Ok(to_amount / from_amount)
+    }
+}
+
+#[derive(Debug)]
+struct CoinGeckoSource;
+
+impl CoinGeckoSource {
+    fn price_endpoint(&self, address: &str) -> String {
+        let mut url = reqwest::Url::parse(
+            "https://api.coingecko.com/api/v3/simple/token_price/binance-smart-chain",
+        )
+        .unwrap();
+        url.query_pairs_mut()
+            .append_pair("contract_addresses", address)
+            .append_pair("vs_currencies", "usd");
+        url.to_string()
+    }
+}
+
+#[async_trait]
+impl PriceSource for CoinGeckoSource {
+    async fn current_price(&self, address: &str) -> Result<f32> {
+        let json: HashMap<String, CoinGeckoPrice> =
+            reqwest::get(self.price_endpoint(address)).await?.json().await?;
+        let entry = json
+            .get(&address.to_lowercase())
+            .ok_or_else(|| anyhow!("coingecko has no price for {}", address))?;
+        Ok(entry.usd)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TickerFrame {
+    #[serde(default)]
+    data: Option<TickerData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TickerData {
+    #[serde(rename = "c")]
+    last_price: String,
+}
+
+#[derive(Debug)]
+struct StreamingPriceSource {
+    symbol: String,
+    prices: Arc<Mutex<HashMap<String, f32>>>,
+}
+
+impl StreamingPriceSource {
+    fn new(ws_url: &str, symbol: &str) -> Self {
+        let prices = Arc::new(Mutex::new(HashMap::new()));
+        let source = Self {
+            symbol: symbol.to_owned(),
+            prices,
+        };
+        let ws_url = ws_url.to_owned();
+        let symbol = source.symbol.clone();
+        let prices = Arc::clone(&source.prices);
+        tokio::spawn(Self::run(ws_url, symbol, prices));
+        source
+    }
+
+    async fn run(ws_url: String, symbol: String, prices: Arc<Mutex<HashMap<String, f32>>>) {
+        let min_backoff = Duration::from_secs(1);
+        let max_backoff = Duration::from_secs(60);
+        let mut backoff = min_backoff;
+
+        loop {
+            let stream = match connect_async(&ws_url).await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    println!("failed to connect {} ticker stream: {}", symbol, err);
+                    sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, max_backoff);
+                    continue;
+                }
+            };
+            println!("connected {} ticker stream", symbol);
+            backoff = min_backoff;
+
+            let (_write, mut read) = stream.split();
+            while let Some(msg) = read.next().await {
+                let txt = match msg {
+                    Ok(WsMessage::Text(txt)) => txt,
+                    Ok(_) => continue, // ping/pong/binary frames carry nothing to cache
+                    Err(err) => {
+                        println!("{} ticker socket closed: {}", symbol, err);
+                        break;
+                    }
+                };
+                let frame: TickerFrame = match serde_json::from_str(&txt) {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        println!("failed to parse {} ticker frame: {}", symbol, err);
+                        continue;
+                    }
+                };
+                let data = match frame.data {
+                    Some(data) => data, // heartbeat/subscription-status frames have no `data`
+                    None => continue,
+                };
+                match data.last_price.parse::<f32>() {
+                    Ok(price) => {
+                        prices.lock().await.insert(symbol.clone(), price);
+                    }
+                    Err(err) => println!("failed to parse {} price: {}", symbol, err),
+                }
             }
+
+            println!("reconnecting {} ticker stream in {:?}", symbol, backoff);
+            sleep(backoff).await;
+            backoff = std::cmp::min(backoff * 2, max_backoff);
         }
-        endpoint
     }
+}
 
-    pub async fn current_price(&self, address: &str) -> Result<f32> {
-        let price_endpoint = self.price_endpoint(address);
-        match self {
-            Self::Pancake => {
-                let json: PancakeResponse = reqwest::get(price_endpoint).await?.json().await?;
-                let price: f32 = json.data.price.parse()?;
-                Ok(price)
-            }
-            Self::OneInch => {
-                let json: OneInchResponse = reqwest::get(price_endpoint).await?.json().await?;
-                let from_amount: f32 = json.from_token_amount.parse()?;
-                let to_amount: f32 = json.to_token_amount.parse()?;
-                Ok(to_amount / from_amount)
-            }
+#[async_trait]
+impl PriceSource for StreamingPriceSource {
+    async fn current_price(&self, _address: &str) -> Result<f32> {
+        self.prices
+            .lock()
+            .await
+            .get(&self.symbol)
+            .copied()
+            .ok_or_else(|| anyhow!("no streamed price cached yet for {}", self.symbol))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TokenConfig {
+    name: String,
+    address: String,
+    feed: String,
+    buy_price: f32,
+    alert_thresold: f32,
+    #[serde(default)]
+    alert_expr: Option<String>,
+    #[serde(default)]
+    whale_threshold: Option<f32>,
+    #[serde(default)]
+    stream_url: Option<String>,
+    #[serde(default)]
+    stream_symbol: Option<String>,
+}
+
+fn eval_alert_expr(expr: &str, price: f32, buy_price: f32, pct: f32) -> Result<bool> {
+    let context = context_map! {
+        "price" => price as f64,
+        "buy_price" => buy_price as f64,
+        "pct" => pct as f64,
+        "abs" => Function::new(|arg| Ok(Value::from(arg.as_number()?.abs()))),
+    }
+    .map_err(|err| anyhow!("fail to build alert_expr context for `{}`: {}", expr, err))?;
+
+    evalexpr::eval_boolean_with_context(expr, &context)
+        .map_err(|err| anyhow!("fail to evaluate alert_expr `{}`: {}", expr, err))
+}
+
+fn price_source_for(config: &TokenConfig) -> Result<Box<dyn PriceSource>> {
+    match config.feed.as_str() {
+        "pancake" => Ok(Box::new(PancakeSource)),
+        "oneinch" => Ok(Box::new(OneInchSource)),
+        "coingecko" => Ok(Box::new(CoinGeckoSource)),
+        "stream" => {
+            let stream_url = config
+                .stream_url
+                .as_deref()
+                .ok_or_else(|| anyhow!("stream feed requires stream_url"))?;
+            let stream_symbol = config
+                .stream_symbol
+                .as_deref()
+                .ok_or_else(|| anyhow!("stream feed requires stream_symbol"))?;
+            Ok(Box::new(StreamingPriceSource::new(stream_url, stream_symbol)))
         }
+        other => Err(anyhow!("unknown price feed: {}", other)),
     }
 }
 
 #[derive(Debug)]
 struct Token {
-    name: &'static str,
-    address: &'static str,
-    price_feed: PriceFeed,
+    name: String,
+    address: String,
+    feed_name: String,
+    price_feed: Box<dyn PriceSource>,
     buy_price: f32,
     alert_thresold: f32,
+    alert_expr: Option<String>,
+    whale_threshold: Option<f32>,
+    stream_url: Option<String>,
+    stream_symbol: Option<String>,
 }
 
 impl Token {
+    fn from_config(config: TokenConfig) -> Result<Self> {
+        let price_feed = price_source_for(&config)?;
+        Ok(Self {
+            name: config.name,
+            address: config.address,
+            feed_name: config.feed,
+            price_feed,
+            buy_price: config.buy_price,
+            alert_thresold: config.alert_thresold,
+            alert_expr: config.alert_expr,
+            whale_threshold: config.whale_threshold,
+            stream_url: config.stream_url,
+            stream_symbol: config.stream_symbol,
+        })
+    }
+
+    fn to_config(&self) -> TokenConfig {
+        TokenConfig {
+            name: self.name.clone(),
+            address: self.address.clone(),
+            feed: self.feed_name.clone(),
+            buy_price: self.buy_price,
+            alert_thresold: self.alert_thresold,
+            alert_expr: self.alert_expr.clone(),
+            whale_threshold: self.whale_threshold,
+            stream_url: self.stream_url.clone(),
+            stream_symbol: self.stream_symbol.clone(),
+        }
+    }
+
     async fn current_price(&self) -> Result<f32> {
-        self.price_feed.current_price(self.address).await
+        self.price_feed.current_price(&self.address).await
     }
 
-    async fn diff_pct(&self) -> Result<(f32, f32, String)> {
-        let current_price = self.current_price().await?;
+    fn diff_pct(&self, current_price: f32) -> (f32, f32, String) {
         let mut sign: char = '\0';
         if current_price > self.buy_price {
             sign = '+';
@@ -116,7 +346,7 @@ impl Token {
             pct = (pct * 100.0).round() / 100.0;
         }
 
-        return Ok((current_price, pct, format!("{}{}%", sign, pct)));
+        (current_price, pct, format!("{}{}%", sign, pct))
     }
 
     fn report_string(&self, current_price: f32, pct_txt: &str) -> String {
@@ -126,50 +356,141 @@ impl Token {
         )
     }
 
-    async fn report(&self) -> Result<String> {
-        let (current_price, _, pct_txt) = self.diff_pct().await?;
-        Ok(self.report_string(current_price, &pct_txt))
+    fn report(&self, snapshot: &PriceSnapshot) -> String {
+        match snapshot.prices.get(&self.name) {
+            Some(&current_price) => {
+                let (_, _, pct_txt) = self.diff_pct(current_price);
+                self.report_string(current_price, &pct_txt)
+            }
+            None => format!("no price cached yet for {}", self.name),
+        }
     }
 
-    async fn check(&self) -> Result<String> {
-        let (current_price, pct, pct_txt) = self.diff_pct().await?;
-        if pct > 0.0 && pct < self.alert_thresold {
-            return Ok("".to_owned());
-        } else if pct <= 0.0 && pct > -self.alert_thresold {
+    fn check(&self, snapshot: &PriceSnapshot) -> Result<String> {
+        let current_price = match snapshot.prices.get(&self.name) {
+            Some(&current_price) => current_price,
+            None => return Ok("".to_owned()),
+        };
+        let (_, pct, pct_txt) = self.diff_pct(current_price);
+        let fires = match &self.alert_expr {
+            Some(expr) => eval_alert_expr(expr, current_price, self.buy_price, pct)?,
+            None => {
+                !(pct > 0.0 && pct < self.alert_thresold || pct <= 0.0 && pct > -self.alert_thresold)
+            }
+        };
+        if !fires {
             return Ok("".to_owned());
         }
         Ok(self.report_string(current_price, &pct_txt))
     }
 }
 
+#[derive(Debug, Clone, Default)]
+struct PriceSnapshot {
+    prices: HashMap<String, f32>,
+}
+
+async fn fetch_price_snapshot(tokens: &Arc<RwLock<Vec<Token>>>) -> PriceSnapshot {
+    let mut prices = HashMap::new();
+    for token in tokens.read().await.iter() {
+        match token.current_price().await {
+            Ok(price) => {
+                prices.insert(token.name.clone(), price);
+            }
+            Err(err) => println!("fail to fetch price for {}: {}", token.name, err),
+        }
+    }
+    PriceSnapshot { prices }
+}
+
+async fn run_price_feed(
+    tokens: Arc<RwLock<Vec<Token>>>,
+    tx: broadcast::Sender<Arc<PriceSnapshot>>,
+    latest: Arc<RwLock<Arc<PriceSnapshot>>>,
+) {
+    let mut interval = interval(Duration::from_secs(60 * 15));
+    loop {
+        interval.tick().await;
+        println!("fetching price snapshot...");
+        let snapshot = Arc::new(fetch_price_snapshot(&tokens).await);
+        *latest.write().await = Arc::clone(&snapshot);
+        let _ = tx.send(snapshot);
+    }
+}
+
+fn load_token_configs(path: &str) -> Result<Vec<TokenConfig>> {
+    if !std::path::Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+    let raw = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&raw)?)
+}
+
+fn save_token_configs(path: &str, configs: &[TokenConfig]) -> Result<()> {
+    let raw = serde_json::to_string_pretty(configs)?;
+    fs::write(path, raw)?;
+    Ok(())
+}
+
 struct Reporter {
-    tokens: Vec<Token>,
+    tokens: Arc<RwLock<Vec<Token>>>,
+    latest_snapshot: Arc<RwLock<Arc<PriceSnapshot>>>,
 }
 
 impl Reporter {
-    async fn report(&self) -> Result<String> {
+    fn persist(&self, tokens: &[Token]) -> Result<()> {
+        let configs: Vec<TokenConfig> = tokens.iter().map(Token::to_config).collect();
+        save_token_configs(TOKENS_FILE, &configs)
+    }
+
+    async fn add_token(&self, config: TokenConfig) -> Result<()> {
+        let token = Token::from_config(config)?;
+        let mut tokens = self.tokens.write().await;
+        tokens.retain(|t| t.name != token.name);
+        tokens.push(token);
+        self.persist(&tokens)
+    }
+
+    async fn remove_token(&self, name: &str) -> Result<()> {
+        let mut tokens = self.tokens.write().await;
+        let before = tokens.len();
+        tokens.retain(|t| t.name != name);
+        if tokens.len() == before {
+            return Err(anyhow!("no such token: {}", name));
+        }
+        self.persist(&tokens)
+    }
+
+    async fn list_tokens(&self) -> String {
+        let tokens = self.tokens.read().await;
+        if tokens.is_empty() {
+            return "watchlist is empty".to_owned();
+        }
+        tokens
+            .iter()
+            .map(|t| {
+                format!(
+                    "{} ({}) feed={} buy_price={} alert_thresold={}",
+                    t.name, t.address, t.feed_name, t.buy_price, t.alert_thresold
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    async fn report(&self, snapshot: &PriceSnapshot) -> String {
         let mut ret = String::new();
-        for token in &self.tokens {
-            let txt = token.report().await;
-            match txt {
-                Ok(txt) => ret.push_str(&txt),
-                Err(err) => ret.push_str(
-                    format!(
-                        "fail to diff pct for token: {}, got error: {}",
-                        token.name, err
-                    )
-                    .as_str(),
-                ),
-            };
+        for token in self.tokens.read().await.iter() {
+            ret.push_str(&token.report(snapshot));
             ret.push_str("\n");
         }
-        return Ok(ret);
+        ret
     }
 
-    async fn check(&self) -> Result<String> {
+    async fn check(&self, snapshot: &PriceSnapshot) -> Result<String> {
         let mut md = String::new();
-        for token in &self.tokens {
-            let txt = token.check().await;
+        for token in self.tokens.read().await.iter() {
+            let txt = token.check(snapshot);
             match txt {
                 Ok(txt) => {
                     if txt != "" {
@@ -203,16 +524,39 @@ impl Reporter {
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         match command {
             Command::P => {
-                let ret = self.report().await;
-                let txt;
-                match ret {
-                    Ok(data) => txt = data,
-                    Err(err) => txt = format!("fail to report: {}", err),
+                let snapshot = Arc::clone(&*self.latest_snapshot.read().await);
+                let txt = self.report(&snapshot).await;
+                cx.answer(markdown::code_block(&txt))
+                    .parse_mode(ParseMode::MarkdownV2)
+                    .await?;
+            }
+            Command::Add(args) => {
+                let txt = match parse_add_args(&args) {
+                    Ok(config) => match self.add_token(config).await {
+                        Ok(()) => "token added".to_owned(),
+                        Err(err) => format!("fail to add token: {}", err),
+                    },
+                    Err(err) => format!("fail to add token: {}", err),
+                };
+                cx.answer(markdown::code_block(&txt))
+                    .parse_mode(ParseMode::MarkdownV2)
+                    .await?;
+            }
+            Command::Remove(name) => {
+                let txt = match self.remove_token(name.trim()).await {
+                    Ok(()) => "token removed".to_owned(),
+                    Err(err) => format!("fail to remove token: {}", err),
                 };
                 cx.answer(markdown::code_block(&txt))
                     .parse_mode(ParseMode::MarkdownV2)
                     .await?;
             }
+            Command::List => {
+                let txt = self.list_tokens().await;
+                cx.answer(markdown::code_block(&txt))
+                    .parse_mode(ParseMode::MarkdownV2)
+                    .await?;
+            }
         };
 
         Ok::<(), Box<dyn Error + Send + Sync>>(())
@@ -222,14 +566,23 @@ impl Reporter {
         &self,
         bot: Arc<AutoSend<DefaultParseMode<Bot>>>,
         chat_id: i64,
+        web3: web3::Web3<web3::transports::Http>,
+        mut price_rx: broadcast::Receiver<Arc<PriceSnapshot>>,
         mut recv: oneshot::Receiver<String>,
     ) {
-        let mut interval = interval(Duration::from_secs(60 * 15));
+        let mut whale_watcher = WhaleWatcher::new(web3, Arc::clone(&self.tokens));
         loop {
             tokio::select! {
-                _ = interval.tick() => {
+                snapshot = price_rx.recv() => {
+                    let snapshot = match snapshot {
+                        Ok(snapshot) => snapshot,
+                        Err(err) => {
+                            println!("price feed channel error: {}", err);
+                            continue;
+                        }
+                    };
                     println!("checking price...");
-                    let txt = self.check().await.unwrap();
+                    let txt = self.check(&snapshot).await.unwrap();
                     if txt == "" {
                         continue;
                     }
@@ -238,6 +591,14 @@ impl Reporter {
                         Err(err) => println!("got error when sending message: {}", err)
                     }
                 },
+                alert = whale_watcher.next_alert() => {
+                    if let Some(alert) = alert {
+                        match bot.send_message(chat_id, &alert).parse_mode(ParseMode::MarkdownV2).await {
+                            Ok(_) => {},
+                            Err(err) => println!("got error when sending whale alert: {}", err)
+                        }
+                    }
+                },
                 msg = &mut recv => {
                     println!("got message: {}", msg.unwrap());
                     break;
@@ -247,52 +608,309 @@ impl Reporter {
     }
 }
 
-#[derive(BotCommand, Debug)]
-#[command(rename = "lowercase", prefix = "P")]
-enum Command {
-    P,
+fn next_weekly_report_time(
+    from: DateTime<Utc>,
+    target_weekday: Weekday,
+    target_hour: u32,
+) -> DateTime<Utc> {
+    let days_ahead =
+        (target_weekday.num_days_from_monday() as i64 - from.weekday().num_days_from_monday() as i64
+            + 7)
+            % 7;
+    let mut next = (from + ChronoDuration::days(days_ahead))
+        .date_naive()
+        .and_hms_opt(target_hour, 0, 0)
+        .unwrap()
+        .and_utc();
+    if next <= from {
+        next += ChronoDuration::days(7);
+    }
+    next
 }
 
-async fn xx() -> Result<()> {
-    let web3 = web3::Web3::new(web3::transports::Http::new("https://bsc-dataseed2.defibit.io/")?);
-    let addr = H160::from_str("0xF339E8c294046E6E7ef6AD4F6fa9E202B59b556B").unwrap();
-    println!("addr {}", addr);
-
-    let w = H160::from_str("0x589b483486c4320C66Cc0ff9FE3A74d31cb9FC37").unwrap();
-
-    let t0 = H256::from_str("0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef").unwrap();
-    println!("t0 {}", t0);
-    // let t1 = H256::from_str("0x589b483486c4320c66cc0ff9fe3a74d31cb9fc37").unwrap();
-    // println!("t1 {}", t1);
-
-    // Filter for Hello event in our contract
-    let filter = FilterBuilder::default()
-        .address(vec![addr])
-        .topics(
-            Some(vec![
-                t0
-            .into()]),
-            None,
-            None,
-            None,
-        )
-        .from_block(BlockNumber::Number(web3::types::U64([14360291])))
-        .build();
+async fn run_weekly_summary(
+    bot: Arc<AutoSend<DefaultParseMode<Bot>>>,
+    chat_id: i64,
+    reporter: Arc<Reporter>,
+) {
+    loop {
+        let now = Utc::now();
+        let next = next_weekly_report_time(now, Weekday::Sun, 15);
+        let wait = (next - now).to_std().unwrap_or(Duration::from_secs(0));
+        sleep(wait).await;
 
-    let filter = web3.eth_filter().create_logs_filter(filter).await?;
+        let snapshot = Arc::clone(&*reporter.latest_snapshot.read().await);
+        let txt = reporter.report(&snapshot).await;
+        match bot
+            .send_message(chat_id, markdown::code_block(&txt))
+            .parse_mode(ParseMode::MarkdownV2)
+            .await
+        {
+            Ok(_) => {}
+            Err(err) => println!("fail to send weekly summary: {}", err),
+        }
+    }
+}
 
-    let logs_stream = filter.stream(Duration::from_secs(1));
-    web3::futures::pin_mut!(logs_stream);
+const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
 
-    let log = logs_stream.next().await.unwrap().unwrap();
-    println!("got log: {:?}", log);
+const ERC20_DECIMALS_ABI: &[u8] =
+    br#"[{"constant":true,"inputs":[],"name":"decimals","outputs":[{"name":"","type":"uint8"}],"payable":false,"stateMutability":"view","type":"function"}]"#;
 
-    let t1 = log.topics[1].to_string();
-    println!("t1 ne {}", t1);
+struct WhaleTarget {
+    name: String,
+    address: H160,
+    decimals: u8,
+    whale_threshold: f32,
+}
 
-    Ok(())
+async fn token_decimals(web3: &web3::Web3<web3::transports::Http>, address: H160) -> Result<u8> {
+    let contract = Contract::from_json(web3.eth(), address, ERC20_DECIMALS_ABI)?;
+    let decimals = contract
+        .query("decimals", (), None, Options::default(), None)
+        .await?;
+    Ok(decimals)
+}
+
+async fn whale_targets(
+    web3: &web3::Web3<web3::transports::Http>,
+    tokens: &Arc<RwLock<Vec<Token>>>,
+) -> Vec<WhaleTarget> {
+    let mut targets = Vec::new();
+    for token in tokens.read().await.iter() {
+        let whale_threshold = match token.whale_threshold {
+            Some(whale_threshold) => whale_threshold,
+            None => continue,
+        };
+        let address = match H160::from_str(&token.address) {
+            Ok(address) => address,
+            Err(err) => {
+                println!("skip whale watch for {}: bad address: {}", token.name, err);
+                continue;
+            }
+        };
+        let decimals = match token_decimals(web3, address).await {
+            Ok(decimals) => decimals,
+            Err(err) => {
+                println!(
+                    "skip whale watch for {}: fail to read decimals: {}",
+                    token.name, err
+                );
+                continue;
+            }
+        };
+        targets.push(WhaleTarget {
+            name: token.name.clone(),
+            address,
+            decimals,
+            whale_threshold,
+        });
+    }
+    targets
+}
+
+fn decode_whale_transfer(log: &Log, target: &WhaleTarget) -> Option<String> {
+    if log.topics.len() < 3 {
+        return None;
+    }
+    if log.data.0.len() != 32 {
+        // `from_big_endian` panics on oversized input; a malformed or
+        // non-standard Transfer-topic log shouldn't take the watcher down.
+        return None;
+    }
+    let from = H160::from_slice(&log.topics[1].as_bytes()[12..]);
+    let to = H160::from_slice(&log.topics[2].as_bytes()[12..]);
+    let value = U256::from_big_endian(&log.data.0);
+    let raw = u128::try_from(value).unwrap_or(u128::MAX);
+    let amount = raw as f64 / 10f64.powi(target.decimals as i32);
+    if (amount as f32) < target.whale_threshold {
+        return None;
+    }
+    Some(format!(
+        "**WHALE ALERT**\n{}: {:.2} moved from {:?} to {:?}",
+        target.name, amount, from, to
+    ))
+}
+
+struct WhaleWatcher {
+    web3: web3::Web3<web3::transports::Http>,
+    tokens: Arc<RwLock<Vec<Token>>>,
+    targets: Vec<WhaleTarget>,
+    stream: Option<Pin<Box<dyn Stream<Item = web3::error::Result<Log>> + Send>>>,
+}
+
+impl WhaleWatcher {
+    fn new(web3: web3::Web3<web3::transports::Http>, tokens: Arc<RwLock<Vec<Token>>>) -> Self {
+        Self {
+            web3,
+            tokens,
+            targets: Vec::new(),
+            stream: None,
+        }
+    }
+
+    async fn ensure_stream(&mut self) {
+        if self.stream.is_some() {
+            return;
+        }
+        self.targets = whale_targets(&self.web3, &self.tokens).await;
+        if self.targets.is_empty() {
+            sleep(Duration::from_secs(60)).await;
+            return;
+        }
+        let addresses: Vec<H160> = self.targets.iter().map(|t| t.address).collect();
+        let transfer_topic = H256::from_str(TRANSFER_TOPIC).unwrap();
+        let filter = FilterBuilder::default()
+            .address(addresses)
+            .topics(Some(vec![transfer_topic]), None, None, None)
+            .build();
+        let eth_filter = match self.web3.eth_filter().create_logs_filter(filter).await {
+            Ok(eth_filter) => eth_filter,
+            Err(err) => {
+                println!("fail to create whale transfer filter: {}", err);
+                sleep(Duration::from_secs(5)).await;
+                return;
+            }
+        };
+        self.stream = Some(Box::pin(eth_filter.stream(Duration::from_secs(1))));
+    }
+
+    async fn next_alert(&mut self) -> Option<String> {
+        loop {
+            self.ensure_stream().await;
+            let stream = match self.stream.as_mut() {
+                Some(stream) => stream,
+                None => continue,
+            };
+            match stream.next().await {
+                Some(Ok(log)) => {
+                    if let Some(target) = self.targets.iter().find(|t| t.address == log.address) {
+                        if let Some(alert) = decode_whale_transfer(&log, target) {
+                            return Some(alert);
+                        }
+                    }
+                }
+                Some(Err(err)) => {
+                    println!("whale transfer stream error: {}", err);
+                    self.stream = None;
+                    sleep(Duration::from_secs(5)).await;
+                }
+                None => {
+                    println!("whale transfer stream closed, reconnecting");
+                    self.stream = None;
+                    sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+}
+
+#[derive(BotCommand, Debug)]
+#[command(rename = "lowercase", prefix = "P")]
+enum Command {
+    P,
+    #[command(description = "/Padd <name> <address> <feed> <buy_price> <alert_thresold> [stream_url] [stream_symbol]")]
+    Add(String),
+    #[command(description = "/Premove <name>")]
+    Remove(String),
+    List,
 }
 
+fn parse_add_args(args: &str) -> Result<TokenConfig> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    match parts.as_slice() {
+        [name, address, feed, buy_price, alert_thresold] => Ok(TokenConfig {
+            name: name.to_string(),
+            address: address.to_string(),
+            feed: feed.to_string(),
+            buy_price: buy_price.parse()?,
+            alert_thresold: alert_thresold.parse()?,
+            alert_expr: None,
+            whale_threshold: None,
+            stream_url: None,
+            stream_symbol: None,
+        }),
+        [name, address, feed, buy_price, alert_thresold, stream_url, stream_symbol] => {
+            Ok(TokenConfig {
+                name: name.to_string(),
+                address: address.to_string(),
+                feed: feed.to_string(),
+                buy_price: buy_price.parse()?,
+                alert_thresold: alert_thresold.parse()?,
+                alert_expr: None,
+                whale_threshold: None,
+                stream_url: Some(stream_url.to_string()),
+                stream_symbol: Some(stream_symbol.to_string()),
+            })
+        }
+        _ => Err(anyhow!(
+            "usage: /Padd <name> <address> <feed> <buy_price> <alert_thresold> [stream_url] [stream_symbol]"
+        )),
+    }
+}
+
+fn default_token_configs() -> Vec<TokenConfig> {
+    vec![
+        TokenConfig {
+            name: "BGS".to_owned(),
+            address: "0xf339e8c294046e6e7ef6ad4f6fa9e202b59b556b".to_owned(),
+            feed: "pancake".to_owned(),
+            buy_price: 0.03,
+            alert_thresold: 30.0,
+            alert_expr: None,
+            whale_threshold: None,
+            stream_url: None,
+            stream_symbol: None,
+        },
+        TokenConfig {
+            name: "ILA".to_owned(),
+            address: "0x4fBEdC7b946e489208DED562e8E5f2bc83B7de42".to_owned(),
+            feed: "pancake".to_owned(),
+            buy_price: 0.01,
+            alert_thresold: 1200.0,
+            alert_expr: None,
+            whale_threshold: None,
+            stream_url: None,
+            stream_symbol: None,
+        },
+        TokenConfig {
+            name: "WOO".to_owned(),
+            address: "0x4691937a7508860f876c9c0a2a617e7d9e945d4b".to_owned(),
+            feed: "pancake".to_owned(),
+            buy_price: 0.75,
+            alert_thresold: 100.0,
+            alert_expr: None,
+            whale_threshold: None,
+            stream_url: None,
+            stream_symbol: None,
+        },
+        TokenConfig {
+            name: "SPARTA".to_owned(),
+            address: "0x3910db0600ea925f63c36ddb1351ab6e2c6eb102".to_owned(),
+            feed: "oneinch".to_owned(),
+            buy_price: 0.0,
+            alert_thresold: 30.0,
+            alert_expr: None,
+            whale_threshold: None,
+            stream_url: None,
+            stream_symbol: None,
+        },
+        TokenConfig {
+            name: "BNB".to_owned(),
+            address: "0xbb4cdb9cbd36b01bd1cbaebf2de08d9173bc095c".to_owned(),
+            feed: "stream".to_owned(),
+            buy_price: 300.0,
+            alert_thresold: 10.0,
+            alert_expr: None,
+            whale_threshold: None,
+            stream_url: Some("wss://stream.binance.com:9443/ws/bnbusdt@ticker".to_owned()),
+            stream_symbol: Some("bnbusdt@ticker".to_owned()),
+        },
+    ]
+}
+
+const BSC_RPC_URL: &str = "https://bsc-dataseed2.defibit.io/";
+
 #[tokio::main]
 async fn main() {
     run().await;
@@ -307,7 +925,7 @@ async fn run() {
         .parse::<i64>()
         .unwrap();
 
-    // let (send, mut recv) = oneshot::channel();
+    let (send, recv) = oneshot::channel();
 
     let bot = Arc::new(
         Bot::from_env()
@@ -315,54 +933,193 @@ async fn run() {
             .auto_send(),
     );
 
+    let web3 = web3::Web3::new(
+        web3::transports::Http::new(BSC_RPC_URL).expect("cannot create web3 http transport"),
+    );
+
+    let configs = load_token_configs(TOKENS_FILE)
+        .unwrap_or_else(|err| {
+            log::warn!("fail to load {}: {}", TOKENS_FILE, err);
+            Vec::new()
+        });
+    let configs = if configs.is_empty() {
+        default_token_configs()
+    } else {
+        configs
+    };
+    let tokens: Vec<Token> = configs
+        .into_iter()
+        .filter_map(|config| match Token::from_config(config) {
+            Ok(token) => Some(token),
+            Err(err) => {
+                log::warn!("fail to load token: {}", err);
+                None
+            }
+        })
+        .collect();
+
+    let tokens = Arc::new(RwLock::new(tokens));
+    let (price_tx, price_rx) = broadcast::channel(16);
+    let latest_snapshot = Arc::new(RwLock::new(Arc::new(PriceSnapshot::default())));
+
     let reporter = Reporter {
-        tokens: vec![
-            Token {
-                name: "BGS",
-                address: "0xf339e8c294046e6e7ef6ad4f6fa9e202b59b556b",
-                price_feed: PriceFeed::Pancake,
-                buy_price: 0.03,
-                alert_thresold: 30.0,
-            },
-            Token {
-                name: "ILA",
-                address: "0x4fBEdC7b946e489208DED562e8E5f2bc83B7de42",
-                price_feed: PriceFeed::Pancake,
-                buy_price: 0.01,
-                alert_thresold: 1200.0,
-            },
-            Token {
-                name: "WOO",
-                address: "0x4691937a7508860f876c9c0a2a617e7d9e945d4b",
-                price_feed: PriceFeed::Pancake,
-                buy_price: 0.75,
-                alert_thresold: 100.0,
-            },
-            Token {
-                name: "SPARTA",
-                address: "0x3910db0600ea925f63c36ddb1351ab6e2c6eb102",
-                price_feed: PriceFeed::OneInch,
-                buy_price: 0.0,
-                alert_thresold: 30.0,
-            },
-        ],
+        tokens: Arc::clone(&tokens),
+        latest_snapshot: Arc::clone(&latest_snapshot),
     };
 
     let reporter = Arc::new(reporter);
-    xx().await;
-
-    // {
-    //     let bot = Arc::clone(&bot);
-    //     let reporter = Arc::clone(&reporter);
-    //     tokio::spawn(async move {
-    //         reporter.watch(bot, chat_id, recv).await;
-    //     });
-    // }
-    // teloxide::commands_repl(Arc::clone(&bot), "fw", move |cx, command| {
-    //     let reporter = Arc::clone(&reporter);
-    //     async move { reporter.cmd(cx, command).await }
-    // })
-    // .await;
-    //
-    // send.send("shutdown".to_owned()).unwrap();
+
+    tokio::spawn(run_price_feed(tokens, price_tx, latest_snapshot));
+
+    {
+        let bot = Arc::clone(&bot);
+        let reporter = Arc::clone(&reporter);
+        tokio::spawn(async move {
+            reporter.watch(bot, chat_id, web3, price_rx, recv).await;
+        });
+    }
+    {
+        let bot = Arc::clone(&bot);
+        let reporter = Arc::clone(&reporter);
+        tokio::spawn(run_weekly_summary(bot, chat_id, reporter));
+    }
+    teloxide::commands_repl(Arc::clone(&bot), "fw", move |cx, command| {
+        let reporter = Arc::clone(&reporter);
+        async move { reporter.cmd(cx, command).await }
+    })
+    .await;
+
+    send.send("shutdown".to_owned()).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use web3::types::Bytes;
+
+    fn transfer_log(topics: Vec<H256>, data: Vec<u8>) -> Log {
+        Log {
+            address: H160::zero(),
+            topics,
+            data: Bytes(data),
+            block_hash: None,
+            block_number: None,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: None,
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    fn whale_target() -> WhaleTarget {
+        WhaleTarget {
+            name: "BGS".to_owned(),
+            address: H160::zero(),
+            decimals: 18,
+            whale_threshold: 1000.0,
+        }
+    }
+
+    #[test]
+    fn decode_whale_transfer_too_few_topics() {
+        let log = transfer_log(vec![H256::zero()], vec![0u8; 32]);
+        assert!(decode_whale_transfer(&log, &whale_target()).is_none());
+    }
+
+    #[test]
+    fn decode_whale_transfer_rejects_oversized_data() {
+        let from = H256::from(H160::from_str("0x1111111111111111111111111111111111111111").unwrap());
+        let to = H256::from(H160::from_str("0x2222222222222222222222222222222222222222").unwrap());
+        let log = transfer_log(vec![H256::zero(), from, to], vec![0u8; 64]);
+        assert!(decode_whale_transfer(&log, &whale_target()).is_none());
+    }
+
+    #[test]
+    fn decode_whale_transfer_below_threshold_is_ignored() {
+        let from = H256::from(H160::from_str("0x1111111111111111111111111111111111111111").unwrap());
+        let to = H256::from(H160::from_str("0x2222222222222222222222222222222222222222").unwrap());
+        let mut data = vec![0u8; 32];
+        U256::from(1u64).to_big_endian(&mut data);
+        let log = transfer_log(vec![H256::zero(), from, to], data);
+        assert!(decode_whale_transfer(&log, &whale_target()).is_none());
+    }
+
+    #[test]
+    fn decode_whale_transfer_above_threshold_alerts() {
+        let from = H256::from(H160::from_str("0x1111111111111111111111111111111111111111").unwrap());
+        let to = H256::from(H160::from_str("0x2222222222222222222222222222222222222222").unwrap());
+        let mut data = vec![0u8; 32];
+        U256::from(2_000_000_000_000_000_000_000u128).to_big_endian(&mut data);
+        let log = transfer_log(vec![H256::zero(), from, to], data);
+        let alert = decode_whale_transfer(&log, &whale_target()).unwrap();
+        assert!(alert.contains("BGS"));
+    }
+
+    #[test]
+    fn decode_whale_transfer_clamps_values_above_u128() {
+        let from = H256::from(H160::from_str("0x1111111111111111111111111111111111111111").unwrap());
+        let to = H256::from(H160::from_str("0x2222222222222222222222222222222222222222").unwrap());
+        let log = transfer_log(vec![H256::zero(), from, to], vec![0xffu8; 32]);
+        let alert = decode_whale_transfer(&log, &whale_target()).unwrap();
+        assert!(alert.contains("BGS"));
+    }
+
+    #[test]
+    fn next_weekly_report_time_rolls_forward_when_passed() {
+        let from = Utc.with_ymd_and_hms(2026, 7, 30, 16, 0, 0).unwrap(); // Thursday 16:00
+        let next = next_weekly_report_time(from, Weekday::Sun, 15);
+        assert_eq!(next, Utc.with_ymd_and_hms(2026, 8, 2, 15, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn next_weekly_report_time_same_day_before_target_hour() {
+        let from = Utc.with_ymd_and_hms(2026, 8, 2, 10, 0, 0).unwrap(); // Sunday 10:00
+        let next = next_weekly_report_time(from, Weekday::Sun, 15);
+        assert_eq!(next, Utc.with_ymd_and_hms(2026, 8, 2, 15, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn eval_alert_expr_or_condition() {
+        assert!(eval_alert_expr("price > 0.05 || pct < -20", 0.1, 0.08, -5.0).unwrap());
+        assert!(!eval_alert_expr("price > 0.05 || pct < -20", 0.01, 0.08, -5.0).unwrap());
+    }
+
+    #[test]
+    fn eval_alert_expr_and_with_abs() {
+        assert!(eval_alert_expr("abs(pct) > 30 && price < 0.1", 0.05, 0.1, -40.0).unwrap());
+        assert!(!eval_alert_expr("abs(pct) > 30 && price < 0.1", 0.2, 0.1, -40.0).unwrap());
+    }
+
+    #[test]
+    fn eval_alert_expr_rejects_garbage() {
+        assert!(eval_alert_expr("not an expr (", 1.0, 1.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn parse_add_args_minimal() {
+        let config = parse_add_args("BGS 0xabc pancake 0.03 30").unwrap();
+        assert_eq!(config.name, "BGS");
+        assert_eq!(config.feed, "pancake");
+        assert_eq!(config.stream_url, None);
+    }
+
+    #[test]
+    fn parse_add_args_with_stream_fields() {
+        let config =
+            parse_add_args("BNB 0xabc stream 300 10 wss://stream.binance.com/ws bnbusdt@ticker")
+                .unwrap();
+        assert_eq!(
+            config.stream_url.as_deref(),
+            Some("wss://stream.binance.com/ws")
+        );
+        assert_eq!(config.stream_symbol.as_deref(), Some("bnbusdt@ticker"));
+    }
+
+    #[test]
+    fn parse_add_args_rejects_wrong_arity() {
+        assert!(parse_add_args("BGS 0xabc pancake 0.03").is_err());
+    }
 }